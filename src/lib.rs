@@ -0,0 +1,15 @@
+//! A small RTP/RTCP implementation, per RFC 3550 (and RFC 4585 for the
+//! AVPF feedback profile).
+
+/// A 32-bit RTP "word": the unit RTP/RTCP timestamps and similar fields
+/// are measured in.
+pub type Word = u32;
+
+/// Synchronization source identifier (RFC 3550 section 3).
+pub type Ssrc = u32;
+
+/// Contributing source identifier (RFC 3550 section 3).
+pub type Csrc = u32;
+
+mod rtcp;
+mod rtp;
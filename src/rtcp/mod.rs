@@ -0,0 +1,2 @@
+mod state;
+mod send_recv_report;
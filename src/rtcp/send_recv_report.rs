@@ -1,34 +1,448 @@
-
-
 use super::super::Ssrc;
 
 static RTP_VERSION: u8 = 10b;
 static SENDER_TYPE: u8 = 200;
 static RECEIVER_TYPE: u8 = 201;
+static SDES_TYPE: u8 = 202;
+
+/// Errors produced while decoding an RTCP packet from the wire.
+///
+/// Malformed input should always be rejected with one of these rather than
+/// by panicking or indexing out of bounds.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ParseError {
+    /// `buf` ended before all of the expected fields could be read.
+    UnexpectedEof,
+    /// The 2-bit version field wasn't `RTP_VERSION`.
+    UnsupportedVersion(u8),
+    /// The packet type byte didn't match what the caller expected.
+    UnexpectedPacketType(u8),
+    /// The decoded `length` field didn't match the number of bytes actually
+    /// consumed while parsing the report blocks that followed.
+    LengthMismatch
+}
+
+#[inline]
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    ((buf[offset] as u32) << 24) | ((buf[offset + 1] as u32) << 16) |
+    ((buf[offset + 2] as u32) << 8) | (buf[offset + 3] as u32)
+}
+
+#[inline]
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.push((value >> 24) as u8);
+    buf.push((value >> 16) as u8);
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+#[inline]
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let hi = read_u32(buf, offset) as u64;
+    let lo = read_u32(buf, offset + 4) as u64;
+    (hi << 32) | lo
+}
 
-struct Header {
-    version: u8,        // RTP version 2 (2 bits)
-    padding: bool,      // indicates the presence of padding for encryption (1 bit)
-    report_count: u8,   // The number of report blocks in this packet (5 bits)
-    packet_type: u8,    // Constant value to identify this packet as a SR packet (8 bits)
-    length: u16,        // Length of this packet + header measured in words - 1. (16 bits)
-    ssrc: Ssrc          // The SSRC of this machine (32 bits)
+#[inline]
+fn write_u64(buf: &mut Vec<u8>, value: u64) {
+    write_u32(buf, (value >> 32) as u32);
+    write_u32(buf, value as u32);
 }
 
-struct SenderInfo {
-    ntp_time: u64,      // NTP wallclock timestamp (64 bits)
-    rtp_time: u32,      // RTP timestamp, very similar to the NTP timestamp (32 bits)
-    packet_count: u32,  // Total number of RTP packets transmitted by this sender (32 bits)
-    octet_count: u32,   // Total number of payload octets transmitted (32 bits)
+pub struct Header {
+    pub version: u8,        // RTP version 2 (2 bits)
+    pub padding: bool,      // indicates the presence of padding for encryption (1 bit)
+    pub report_count: u8,   // The number of report blocks in this packet (5 bits)
+    pub packet_type: u8,    // Identifies the packet kind, e.g. SR (200) or RR (201) (8 bits)
+    pub length: u16,        // Length of this packet + header measured in words - 1. (16 bits)
+    pub ssrc: Ssrc          // The SSRC of this machine (32 bits)
 }
 
-struct ReportBlock {
-    ssrc: Ssrc,         // The SSRC of the source to which this block pertains (32 bits)
-    lost: u8,           // Fraction of RTP packets lost since the previous report (8 bits)
-    lost_total: u32,    // Total number of lost RTP packets from this source (24 bits)
-    highest_seq: u32,   // Highest sequence number received in an RTP packet (32 bits)
-    jitter: u32,        // Estimate of interarrival jitter from this source (32 bits)
-    last_sr: u32,       // Time of last SR received from this source
-    sr_delay: u32,      // Delay between the last SR and sending this RR (32 bits)
+impl Header {
+    /// Encodes this header onto the wire in network byte order, per RFC
+    /// 3550 section 6.4.1.
+    #[allow(dead_code)]
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        let byte0 = ((self.version & 0x3) << 6) |
+                    ((self.padding as u8) << 5) |
+                    (self.report_count & 0x1f);
+
+        buf.push(byte0);
+        buf.push(self.packet_type);
+        buf.push((self.length >> 8) as u8);
+        buf.push(self.length as u8);
+        write_u32(buf, self.ssrc);
+    }
+
+    /// Decodes a header from the front of `buf`, returning it along with the
+    /// number of bytes consumed (always 8). Only the version field is
+    /// validated here; callers check `packet_type` against the packet kind
+    /// they're expecting to parse.
+    #[allow(dead_code)]
+    pub fn from_bytes(buf: &[u8]) -> Result<(Header, usize), ParseError> {
+        if buf.len() < 8 {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let version = buf[0] >> 6;
+        if version != RTP_VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+
+        let header = Header {
+            version: version,
+            padding: (buf[0] & 0x20) != 0,
+            report_count: buf[0] & 0x1f,
+            packet_type: buf[1],
+            length: ((buf[2] as u16) << 8) | (buf[3] as u16),
+            ssrc: read_u32(buf, 4)
+        };
+
+        Ok((header, 8))
+    }
 }
 
+pub struct SenderInfo {
+    pub ntp_time: u64,      // NTP wallclock timestamp (64 bits)
+    pub rtp_time: u32,      // RTP timestamp, very similar to the NTP timestamp (32 bits)
+    pub packet_count: u32,  // Total number of RTP packets transmitted by this sender (32 bits)
+    pub octet_count: u32,   // Total number of payload octets transmitted (32 bits)
+}
+
+impl SenderInfo {
+    #[allow(dead_code)]
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        write_u64(buf, self.ntp_time);
+        write_u32(buf, self.rtp_time);
+        write_u32(buf, self.packet_count);
+        write_u32(buf, self.octet_count);
+    }
+
+    #[allow(dead_code)]
+    pub fn from_bytes(buf: &[u8]) -> Result<(SenderInfo, usize), ParseError> {
+        if buf.len() < 20 {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let info = SenderInfo {
+            ntp_time: read_u64(buf, 0),
+            rtp_time: read_u32(buf, 8),
+            packet_count: read_u32(buf, 12),
+            octet_count: read_u32(buf, 16)
+        };
+
+        Ok((info, 20))
+    }
+}
+
+pub struct ReportBlock {
+    pub ssrc: Ssrc,         // The SSRC of the source to which this block pertains (32 bits)
+    pub lost: u8,           // Fraction of RTP packets lost since the previous report (8 bits)
+    pub lost_total: u32,    // Total number of lost RTP packets from this source (24 bits)
+    pub highest_seq: u32,   // Highest sequence number received in an RTP packet (32 bits)
+    pub jitter: u32,        // Estimate of interarrival jitter from this source (32 bits)
+    pub last_sr: u32,       // Time of last SR received from this source
+    pub sr_delay: u32,      // Delay between the last SR and sending this RR (32 bits)
+}
+
+impl ReportBlock {
+    #[allow(dead_code)]
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        write_u32(buf, self.ssrc);
+
+        buf.push(self.lost);
+        buf.push((self.lost_total >> 16) as u8);
+        buf.push((self.lost_total >> 8) as u8);
+        buf.push(self.lost_total as u8);
+
+        write_u32(buf, self.highest_seq);
+        write_u32(buf, self.jitter);
+        write_u32(buf, self.last_sr);
+        write_u32(buf, self.sr_delay);
+    }
+
+    #[allow(dead_code)]
+    pub fn from_bytes(buf: &[u8]) -> Result<(ReportBlock, usize), ParseError> {
+        if buf.len() < 24 {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let block = ReportBlock {
+            ssrc: read_u32(buf, 0),
+            lost: buf[4],
+            lost_total: ((buf[5] as u32) << 16) | ((buf[6] as u32) << 8) | (buf[7] as u32),
+            highest_seq: read_u32(buf, 8),
+            jitter: read_u32(buf, 12),
+            last_sr: read_u32(buf, 16),
+            sr_delay: read_u32(buf, 20)
+        };
+
+        Ok((block, 24))
+    }
+}
+
+/// A full RFC 3550 Sender Report: a source that has sent RTP data reporting
+/// both its own transmission statistics (`sender_info`) and what it has
+/// observed of other sources (`reports`).
+#[allow(dead_code)]
+pub struct SenderReport {
+    pub header: Header,
+    pub sender_info: SenderInfo,
+    pub reports: Vec<ReportBlock>
+}
+
+impl SenderReport {
+    #[allow(dead_code)]
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        self.header.to_bytes(buf);
+        self.sender_info.to_bytes(buf);
+        for report in self.reports.iter() {
+            report.to_bytes(buf);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_bytes(buf: &[u8]) -> Result<(SenderReport, usize), ParseError> {
+        let (header, mut offset) = try!(Header::from_bytes(buf));
+        if header.packet_type != SENDER_TYPE {
+            return Err(ParseError::UnexpectedPacketType(header.packet_type));
+        }
+
+        let (sender_info, consumed) = try!(SenderInfo::from_bytes(&buf[offset..]));
+        offset += consumed;
+
+        let mut reports = Vec::with_capacity(header.report_count as usize);
+        for _ in range(0, header.report_count) {
+            let (report, consumed) = try!(ReportBlock::from_bytes(&buf[offset..]));
+            reports.push(report);
+            offset += consumed;
+        }
+
+        if header.length as usize != (offset / 4) - 1 {
+            return Err(ParseError::LengthMismatch);
+        }
+
+        Ok((SenderReport { header: header, sender_info: sender_info, reports: reports }, offset))
+    }
+}
+
+/// A full RFC 3550 Receiver Report: like `SenderReport`, but from a
+/// participant that hasn't sent any RTP data itself, so there's no
+/// `SenderInfo` block.
+#[allow(dead_code)]
+pub struct ReceiverReport {
+    pub header: Header,
+    pub reports: Vec<ReportBlock>
+}
+
+impl ReceiverReport {
+    #[allow(dead_code)]
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        self.header.to_bytes(buf);
+        for report in self.reports.iter() {
+            report.to_bytes(buf);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_bytes(buf: &[u8]) -> Result<(ReceiverReport, usize), ParseError> {
+        let (header, mut offset) = try!(Header::from_bytes(buf));
+        if header.packet_type != RECEIVER_TYPE {
+            return Err(ParseError::UnexpectedPacketType(header.packet_type));
+        }
+
+        let mut reports = Vec::with_capacity(header.report_count as usize);
+        for _ in range(0, header.report_count) {
+            let (report, consumed) = try!(ReportBlock::from_bytes(&buf[offset..]));
+            reports.push(report);
+            offset += consumed;
+        }
+
+        if header.length as usize != (offset / 4) - 1 {
+            return Err(ParseError::LengthMismatch);
+        }
+
+        Ok((ReceiverReport { header: header, reports: reports }, offset))
+    }
+}
+
+/// Concatenates an encoded SR or RR with an encoded SDES packet into a
+/// single RFC 3550 6.1 compound packet (every compound packet must begin
+/// with a report and be immediately followed by SDES). SDES isn't modeled
+/// by this crate yet, so `sdes_bytes` is taken pre-encoded; this function
+/// still validates that both halves carry sane RTCP headers rather than
+/// blindly gluing untrusted buffers together.
+#[allow(dead_code)]
+pub fn build_compound_packet(report_bytes: &[u8], sdes_bytes: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let (report_header, _) = try!(Header::from_bytes(report_bytes));
+    if report_header.packet_type != SENDER_TYPE && report_header.packet_type != RECEIVER_TYPE {
+        return Err(ParseError::UnexpectedPacketType(report_header.packet_type));
+    }
+
+    let (sdes_header, _) = try!(Header::from_bytes(sdes_bytes));
+    if sdes_header.packet_type != SDES_TYPE {
+        return Err(ParseError::UnexpectedPacketType(sdes_header.packet_type));
+    }
+
+    let mut buf = Vec::with_capacity(report_bytes.len() + sdes_bytes.len());
+    buf.push_all(report_bytes);
+    buf.push_all(sdes_bytes);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_header(packet_type: u8, report_count: u8, length: u16) -> Header {
+        Header {
+            version: RTP_VERSION,
+            padding: false,
+            report_count: report_count,
+            packet_type: packet_type,
+            length: length,
+            ssrc: 0xdeadbeef
+        }
+    }
+
+    fn sample_block(ssrc: Ssrc) -> ReportBlock {
+        ReportBlock {
+            ssrc: ssrc,
+            lost: 12,
+            lost_total: 0xabcdef, // sets bits in all three bytes of the 24-bit field
+            highest_seq: 99,
+            jitter: 55,
+            last_sr: 0,
+            sr_delay: 0
+        }
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let header = sample_header(SENDER_TYPE, 1, 6);
+
+        let mut buf = Vec::new();
+        header.to_bytes(&mut buf);
+
+        let (decoded, consumed) = Header::from_bytes(&buf).unwrap();
+        assert_eq!(consumed, 8);
+        assert_eq!(decoded.version, header.version);
+        assert_eq!(decoded.padding, header.padding);
+        assert_eq!(decoded.report_count, header.report_count);
+        assert_eq!(decoded.packet_type, header.packet_type);
+        assert_eq!(decoded.length, header.length);
+        assert_eq!(decoded.ssrc, header.ssrc);
+    }
+
+    #[test]
+    fn sender_info_round_trips() {
+        let info = SenderInfo {
+            ntp_time: 0x0102030405060708,
+            rtp_time: 42,
+            packet_count: 7,
+            octet_count: 1024
+        };
+
+        let mut buf = Vec::new();
+        info.to_bytes(&mut buf);
+
+        let (decoded, consumed) = SenderInfo::from_bytes(&buf).unwrap();
+        assert_eq!(consumed, 20);
+        assert_eq!(decoded.ntp_time, info.ntp_time);
+        assert_eq!(decoded.rtp_time, info.rtp_time);
+        assert_eq!(decoded.packet_count, info.packet_count);
+        assert_eq!(decoded.octet_count, info.octet_count);
+    }
+
+    #[test]
+    fn report_block_round_trips_24_bit_lost_total() {
+        let block = sample_block(0xcafef00d);
+
+        let mut buf = Vec::new();
+        block.to_bytes(&mut buf);
+
+        let (decoded, consumed) = ReportBlock::from_bytes(&buf).unwrap();
+        assert_eq!(consumed, 24);
+        assert_eq!(decoded.ssrc, block.ssrc);
+        assert_eq!(decoded.lost, block.lost);
+        assert_eq!(decoded.lost_total, block.lost_total);
+        assert_eq!(decoded.highest_seq, block.highest_seq);
+        assert_eq!(decoded.jitter, block.jitter);
+    }
+
+    #[test]
+    fn sender_report_round_trips_with_reports() {
+        // length = (header + sender_info + 1 report block) / 4 words - 1
+        let report = SenderReport {
+            header: sample_header(SENDER_TYPE, 1, 12),
+            sender_info: SenderInfo {
+                ntp_time: 1, rtp_time: 2, packet_count: 3, octet_count: 4
+            },
+            reports: vec![sample_block(0x1)]
+        };
+
+        let mut buf = Vec::new();
+        report.to_bytes(&mut buf);
+
+        let (decoded, consumed) = SenderReport::from_bytes(&buf).unwrap();
+        assert_eq!(consumed, buf.len());
+        assert_eq!(decoded.reports.len(), 1);
+        assert_eq!(decoded.sender_info.octet_count, 4);
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated_input() {
+        let buf = vec![0u8; 4]; // shorter than an 8-byte header
+
+        match Header::from_bytes(&buf) {
+            Err(ParseError::UnexpectedEof) => (),
+            _ => panic!("expected UnexpectedEof for a truncated header"),
+        }
+    }
+
+    #[test]
+    fn from_bytes_rejects_unsupported_version() {
+        let mut header = sample_header(SENDER_TYPE, 0, 0);
+        header.version = 0; // anything but RTP_VERSION
+
+        let mut buf = Vec::new();
+        header.to_bytes(&mut buf);
+
+        match Header::from_bytes(&buf) {
+            Err(ParseError::UnsupportedVersion(0)) => (),
+            _ => panic!("expected UnsupportedVersion"),
+        }
+    }
+
+    #[test]
+    fn sender_report_rejects_wrong_packet_type() {
+        let mut buf = Vec::new();
+        sample_header(RECEIVER_TYPE, 0, 0).to_bytes(&mut buf);
+
+        match SenderReport::from_bytes(&buf) {
+            Err(ParseError::UnexpectedPacketType(pt)) => assert_eq!(pt, RECEIVER_TYPE),
+            _ => panic!("expected UnexpectedPacketType"),
+        }
+    }
+
+    #[test]
+    fn sender_report_rejects_length_mismatch() {
+        // Claims 2 report blocks worth of length, but only encodes 1.
+        let report = SenderReport {
+            header: sample_header(SENDER_TYPE, 1, 18),
+            sender_info: SenderInfo {
+                ntp_time: 0, rtp_time: 0, packet_count: 0, octet_count: 0
+            },
+            reports: vec![sample_block(0x1)]
+        };
+
+        let mut buf = Vec::new();
+        report.to_bytes(&mut buf);
+
+        match SenderReport::from_bytes(&buf) {
+            Err(ParseError::LengthMismatch) => (),
+            _ => panic!("expected LengthMismatch"),
+        }
+    }
+}
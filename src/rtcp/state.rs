@@ -5,10 +5,75 @@ use self::time::SteadyTime;
 use std::rand::{random, Closed01};
 use std::cmp;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::time::Duration;
 use std::i64;
 
 use super::super::{Ssrc, Csrc};
+use super::send_recv_report::ReportBlock;
+
+/// RFC 3550 A.1: the largest forward sequence number jump that's still
+/// treated as in-order (anything bigger looks like the source restarted).
+#[allow(dead_code)]
+const MAX_DROPOUT: u16 = 3000;
+
+/// RFC 3550 A.1: the largest backward jump that's still treated as a
+/// reordered (rather than old/duplicate) packet.
+#[allow(dead_code)]
+const MAX_MISORDER: u16 = 100;
+
+/// RFC 3550 6.3.5: a member is timed out after this many
+/// `sweep_stale_members` passes without hearing from it.
+#[allow(dead_code)]
+const RTCP_SOURCE_TIMEOUT_N_INTERVALS: i32 = 5;
+
+/// RFC 3550 6.3.5: a sender is demoted back to `Listening` after this many
+/// `sweep_stale_members` passes without a packet from it.
+#[allow(dead_code)]
+const RTCP_SENDER_TIMEOUT_N_INTERVALS: i32 = 2;
+
+/// RFC 3556 / RFC 3550 6.2: the smallest RTCP bandwidth we'll actually
+/// compute an interval against, in octets per second. Guards against a
+/// misconfigured near-zero bandwidth blowing up (or, after the float math
+/// and microsecond truncation, silently collapsing) the transmission
+/// interval.
+#[allow(dead_code)]
+const MIN_RTCP_BANDWIDTH: i32 = 400;
+
+/// RFC 3550 8.2: how long a (SSRC, address) conflict is remembered and
+/// suppressed after being acted on, as a multiple of the deterministic report
+/// interval (`deterministic_tx_interval`), so that a transient network loop doesn't trigger reassignment
+/// or repeated collision events over and over.
+#[allow(dead_code)]
+const CONFLICT_SUPPRESS_N_INTERVALS: i32 = 12;
+
+/// Reported by `pkt_recv_notify` when an SSRC collision is detected, per
+/// RFC 3550 8.2.
+#[allow(dead_code)]
+pub enum CollisionEvent {
+    /// A third-party collision: two different transport addresses are
+    /// claiming the same (non-local) SSRC. No local state changes; this is
+    /// surfaced so the host can log it or flag the remote participants.
+    ThirdParty(Ssrc),
+    /// Our own SSRC collided with another source. `new` was drawn as a
+    /// replacement for `old`, already reflected in `member_table`. `State`
+    /// itself sends no BYE for `old`.
+    OwnReassigned { old: Ssrc, new: Ssrc }
+}
+
+/// The parts of a received RTP packet needed to update interarrival jitter
+/// and loss statistics (RFC 3550 Appendix A.1/A.8). Only meaningful when
+/// `pkt_recv_notify` is called with `PacketType::Rtp`.
+#[allow(dead_code)]
+pub struct RtpRecvInfo {
+    pub sequence_number: u16,
+    pub rtp_timestamp: u32,
+    // The arrival time of this packet, expressed in the same RTP clock
+    // units as `rtp_timestamp`. Converting the local wallclock into this
+    // domain is the caller's responsibility, since it depends on the
+    // media clock rate, which this crate doesn't track.
+    pub arrival_rtp_ts: u32
+}
 
 #[allow(dead_code)]
 enum PacketType {
@@ -27,6 +92,33 @@ enum MemberState {
     Bye
 }
 
+/// Selects which RTCP timing rules a `State` follows.
+///
+/// `Avp` is the plain RFC 3550 profile, where reports are sent on the
+/// regular, randomized interval computed by `tx_interval`. `Avpf` is the
+/// RFC 4585 "Audio-Visual Profile with Feedback"
+/// (tools.ietf.org/html/rfc4585), which allows time-sensitive feedback
+/// (PLI/FIR/NACK) to be sent out-of-band via `request_early_rtcp` instead
+/// of waiting for the next regular report.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RtpProfile {
+    Avp,
+    Avpf
+}
+
+/// Outcome of a `request_early_rtcp` call.
+#[allow(dead_code)]
+pub enum EarlyRtcpResult {
+    /// An early feedback packet was scheduled for transmission at `tn`.
+    Scheduled,
+    /// A regular report is already imminent, so no early packet is needed.
+    Suppressed,
+    /// We are still inside the mandatory regular-report-only window; `tn`
+    /// was recomputed via timer reconsideration (6.3.3) instead.
+    Reconsidered
+}
+
 #[allow(dead_code)]
 struct Member {
     id: Ssrc,
@@ -35,6 +127,44 @@ struct Member {
     intervals: i32  // TX intervals since last packet seen
 }
 
+/// Per-source RTP receive statistics, maintained per RFC 3550 Appendix A.1,
+/// A.3 and A.8 so they can be packaged into an outgoing `ReportBlock`.
+#[allow(dead_code)]
+struct ReceiveStats {
+    base_seq: u16,          // First sequence number seen from this source
+    max_seq: u16,           // Highest sequence number seen (mod 2^16)
+    cycles: u32,            // Number of times max_seq has wrapped around
+    received: u32,          // Count of packets received from this source
+    prior_expected: u32,    // `expected` as of the last report snapshot
+    prior_received: u32,    // `received` as of the last report snapshot
+    transit: Option<i64>,   // Transit time D of the previous packet
+    jitter: f64,            // Running interarrival jitter estimate J
+    bad_seq: Option<u16>    // Awaiting confirmation of a large sequence jump
+}
+
+impl ReceiveStats {
+    fn new(seq: u16) -> ReceiveStats {
+        ReceiveStats {
+            base_seq: seq,
+            max_seq: seq,
+            cycles: 0,
+            received: 0,
+            prior_expected: 0,
+            prior_received: 0,
+            transit: None,
+            jitter: 0.0,
+            bad_seq: None
+        }
+    }
+
+    /// Total packets expected so far, per RFC 3550 A.3: the span of the
+    /// sequence number space seen, independent of how many actually arrived.
+    fn expected(&self) -> u32 {
+        let extended_max = (self.cycles << 16) | self.max_seq as u32;
+        extended_max - self.base_seq as u32 + 1
+    }
+}
+
 #[allow(dead_code)]
 struct State {
     tp: SteadyTime,     // The last time an RTCP packet was transmitted
@@ -44,10 +174,22 @@ struct State {
     members: i32,       // Current estimate of member count
     senders: i32,       // Current estimate of sender count
     rtcp_bw: i32,       // Target RTCP bandwidth, in octets per second
+    rtcp_bw_sender: Option<i32>,    // RFC 3556 RS: bandwidth reserved for senders
+    rtcp_bw_receiver: Option<i32>,  // RFC 3556 RR: bandwidth reserved for receivers
     we_sent: bool,      // Flag: True if application sent data recently
     avg_rtcp_size: f32, // Average compound RTCP packet size, in octets
     initial: bool,      // Flag: True if a packet has not yet been sent
-    member_table: HashMap<Ssrc, Member> // A List of all members of the current session
+    member_table: HashMap<Ssrc, Member>, // A List of all members of the current session
+    receive_stats: HashMap<Ssrc, ReceiveStats>, // Per-source jitter/loss tracking
+
+    our_ssrc: Ssrc,                    // This application's current SSRC
+    our_addr: SocketAddr,              // This application's own transport address
+    known_addrs: HashMap<Ssrc, SocketAddr>,          // Last transport address seen for each SSRC
+    conflicts: HashMap<(Ssrc, SocketAddr), SteadyTime>, // Recently-handled (SSRC, address) conflicts
+
+    profile: RtpProfile,    // RFC 3550 (Avp) vs RFC 4585 (Avpf) timing rules
+    t_rr_interval: Duration,// AVPF: minimum interval between regular reports
+    allow_early: bool       // AVPF: true if we're inside the "early send" window
 }
 
 impl State {
@@ -65,18 +207,45 @@ impl State {
     ///
     /// * `our_ssrc` - The application's SSRC, used to uniquely identify this
     ///                synchronization source to participants in the session.
+    /// * `our_addr` - The transport address RTP/RTCP packets are sent from.
+    ///                Used to tell a genuine SSRC collision (RFC 3550 8.2)
+    ///                apart from our own packets looping back to us.
     /// * `bandwidth` - The fraction of session bandwidth available to *all* RTCP
     ///                 participants, in octets per second. This quantity
     ///                 is fixed during startup.
     /// * `pkt_size` - Best guess as to the size of the first RTCP packet which
     ///                will be later constructed. This can be off a bit, but it
-    ///                helps to be close. 
+    ///                helps to be close.
     ///
     /// # Return Value
     ///
-    /// The returned State object holds the state of the current RTCP session. 
+    /// The returned State object holds the state of the current RTCP session.
+    #[allow(dead_code)]
+    pub fn initialize(our_ssrc: Ssrc, our_addr: SocketAddr, bandwidth: i32, pkt_size: i32) -> State {
+        State::initialize_with_profile(our_ssrc, our_addr, bandwidth, pkt_size, RtpProfile::Avp,
+                                        Duration::zero())
+    }
+
+    /// Initializes an RTCP session using the RFC 4585 AVPF profile.
+    ///
+    /// This behaves exactly like `initialize`, except that `request_early_rtcp`
+    /// becomes usable for low-latency feedback, and the regular report
+    /// interval is governed by `t_rr_interval` rather than the fixed
+    /// 2.5s/5s RFC 3550 floor. See RFC 4585 section 3.5.
+    ///
+    /// # Arguments
+    ///
+    /// * `t_rr_interval` - The minimum interval between regular RTCP
+    ///                     reports. May be zero for two-party sessions.
     #[allow(dead_code)]
-    pub fn initialize(our_ssrc: Ssrc, bandwidth: i32, pkt_size: i32) -> State {
+    pub fn initialize_avpf(our_ssrc: Ssrc, our_addr: SocketAddr, bandwidth: i32, pkt_size: i32,
+                           t_rr_interval: Duration) -> State {
+        State::initialize_with_profile(our_ssrc, our_addr, bandwidth, pkt_size, RtpProfile::Avpf,
+                                        t_rr_interval)
+    }
+
+    fn initialize_with_profile(our_ssrc: Ssrc, our_addr: SocketAddr, bandwidth: i32, pkt_size: i32,
+                               profile: RtpProfile, t_rr_interval: Duration) -> State {
         let mut result = State {
             tp: SteadyTime::now(),
             tc: SteadyTime::now(),
@@ -85,23 +254,43 @@ impl State {
             members: 1,
             senders: 0,
             rtcp_bw: bandwidth,
+            rtcp_bw_sender: None,
+            rtcp_bw_receiver: None,
             we_sent: false,
             avg_rtcp_size: pkt_size as f32,
             initial: true,
-            member_table: HashMap::with_capacity(32)
+            member_table: HashMap::with_capacity(32),
+            receive_stats: HashMap::with_capacity(32),
+            our_ssrc: our_ssrc,
+            our_addr: our_addr,
+            known_addrs: HashMap::with_capacity(32),
+            conflicts: HashMap::with_capacity(4),
+            profile: profile,
+            t_rr_interval: t_rr_interval,
+            allow_early: false
         };
 
         // Add ourselves to the member table as a listener
-        result.member_table.insert(our_ssrc, 
-                                   Member { id: our_ssrc, cname: None, 
+        result.member_table.insert(our_ssrc,
+                                   Member { id: our_ssrc, cname: None,
                                             status: Some(MemberState::Listening),
                                             intervals: 0});
-        
+
         // Calculate the initial tx interval and return
         result.tn = result.tc + result.tx_interval();
         result
     }
 
+    /// Configures separate sender (RS) and receiver (RR) RTCP bandwidth
+    /// fractions, per RFC 3556. Once set, `tx_interval` derives its
+    /// interval from whichever of `sender_bw`/`receiver_bw` applies to our
+    /// current role instead of splitting the single `rtcp_bw` 25%/75%.
+    #[allow(dead_code)]
+    pub fn set_bandwidth_fractions(&mut self, sender_bw: i32, receiver_bw: i32) {
+        self.rtcp_bw_sender = Some(sender_bw);
+        self.rtcp_bw_receiver = Some(receiver_bw);
+    }
+
     /// Computes the RTCP Transmission Interval based on the current session state.
     ///
     /// The time interval between transmissions of RTCP packets varies with the number
@@ -115,62 +304,100 @@ impl State {
     /// The return value is the time interval between RTCP packets, in seconds.
     #[allow(unstable)]
     pub fn tx_interval(&self) -> Duration {
-        
-        let few_senders = self.senders as f32 <= 0.25 * self.members as f32;
+        let t_d = self.deterministic_tx_interval();
+
+        let t_d_micros = match t_d.num_microseconds() {
+            Some(micros)=> micros,
+            None        => i64::MAX // Assumption: None is always an overflow
+        };
+
+        let Closed01(rand) = random::<Closed01<f64>>();
+
+        let t_rand = ( t_d_micros as f64 / 2.0 ) +
+                     ( rand * t_d_micros as f64 );
+        Duration::microseconds((t_rand / 1.21828) as i64)
+    }
+
+    /// Computes `T_d`, the non-randomized transmission interval that
+    /// `tx_interval` dithers around. This is also the unit of the collision
+    /// suppression window in `check_collision`. The member timeouts in
+    /// `sweep_stale_members` are not derived from it; they count sweep passes.
+    #[allow(unstable)]
+    fn deterministic_tx_interval(&self) -> Duration {
+        // RFC 3556: when the application has separately budgeted sender and
+        // receiver bandwidth, use whichever share applies to our own role
+        // instead of re-deriving a 25%/75% split from a single total.
+        let c_times_n = match (self.rtcp_bw_sender, self.rtcp_bw_receiver) {
+            (Some(rs), Some(rr)) => {
+                let bw = if self.we_sent { rs } else { rr };
+                let n = if self.we_sent { self.senders } else { self.members - self.senders };
+
+                Duration::microseconds((self.avg_rtcp_size as f32 /
+                                        cmp::max(bw, MIN_RTCP_BANDWIDTH) as f32 *
+                                        1000000.0 ) as i64 *
+                                       n as i64)
+            },
+
+            _ => {
+                let few_senders = self.senders as f32 <= 0.25 * self.members as f32;
+                let rtcp_bw = cmp::max(self.rtcp_bw, MIN_RTCP_BANDWIDTH);
 
-        let c_times_n = match few_senders {
-            true => {
-                match self.we_sent {
+                match few_senders {
                     true => {
-                        Duration::microseconds((self.avg_rtcp_size as f32 / 
-                                                self.rtcp_bw as f32 * 
-                                                0.25 * 
-                                                1000000.0 ) as i64 * 
-                                               self.senders as i64)
+                        match self.we_sent {
+                            true => {
+                                Duration::microseconds((self.avg_rtcp_size as f32 /
+                                                        rtcp_bw as f32 *
+                                                        0.25 *
+                                                        1000000.0 ) as i64 *
+                                                       self.senders as i64)
+                            },
+
+                            false => {
+                                Duration::microseconds((self.avg_rtcp_size as f32 /
+                                                        rtcp_bw as f32 *
+                                                        0.75 *
+                                                        1000000.0 ) as i64 *
+                                                       (self.members - self.senders) as i64)
+                            },
+                        }
                     },
-                    
+
                     false => {
                         Duration::microseconds((self.avg_rtcp_size as f32 /
-                                                self.rtcp_bw as f32 *
-                                                0.75 *
+                                                rtcp_bw as f32 *
                                                 1000000.0 ) as i64 *
-                                               (self.members - self.senders) as i64)
+                                               self.members as i64)
                     },
                 }
             },
-            
-            false => {
-                Duration::microseconds((self.avg_rtcp_size as f32 /
-                                        self.rtcp_bw as f32 *
-                                        1000000.0 ) as i64 *
-                                       self.members as i64)
-            },
         };
 
-        let t_min = if self.initial {
-            Duration::milliseconds(2500)
-        } else {
-            Duration::milliseconds(5000)
-        };
-        
-        let t_d = cmp::max(t_min, c_times_n);
+        let t_min = match self.profile {
+            RtpProfile::Avp => if self.initial {
+                Duration::milliseconds(2500)
+            } else {
+                Duration::milliseconds(5000)
+            },
 
-        let t_d_micros = match t_d.num_microseconds() {
-            Some(micros)=> micros,
-            None        => i64::MAX // Assumption: None is always an overflow
+            // RFC 4585 3.5: the regular-report floor is governed by the
+            // configured T_rr_interval instead of the fixed 2.5s/5s minimum,
+            // and may be zero for a point-to-point (two-party) session.
+            RtpProfile::Avpf => self.t_rr_interval,
         };
 
-        let Closed01(rand) = random::<Closed01<f64>>();
-
-        let t_rand = ( t_d_micros as f64 / 2.0 ) + 
-                     ( rand * t_d_micros as f64 );
-        Duration::microseconds((t_rand / 1.21828) as i64)
+        cmp::max(t_min, c_times_n)
     }
 
     #[allow(dead_code)]
     #[allow(unused_variables)]
-    pub fn pkt_recv_notify(&mut self, packet_type: PacketType, packet_size: i32, 
-                       ssrc: Ssrc, csrcs: &[Csrc]) {
+    pub fn pkt_recv_notify(&mut self, packet_type: PacketType, packet_size: i32,
+                       ssrc: Ssrc, csrcs: &[Csrc], from: SocketAddr, now: SteadyTime,
+                       rtp_info: Option<RtpRecvInfo>) -> Option<CollisionEvent> {
+        self.tc = now;
+
+        let collision = self.check_collision(ssrc, from);
+
         match packet_type{
             PacketType::Bye => {
                 match self.member_table.get_mut(&ssrc) {
@@ -201,6 +428,11 @@ impl State {
                 for &ident in csrcs.iter() {
                     self.update_member_status(ident, false);
                 }
+
+                if let Some(info) = rtp_info {
+                    self.update_receive_stats(ssrc, info.sequence_number,
+                                              info.rtp_timestamp, info.arrival_rtp_ts);
+                }
             },
             
             _ => {
@@ -212,6 +444,81 @@ impl State {
         }
 
         self.avg_rtcp_size = self.update_avg_packet_size(packet_size);
+
+        collision
+    }
+
+    /// Checks whether `ssrc` has just been claimed by a new transport
+    /// address, per RFC 3550 8.2. If the colliding SSRC is our own, a fresh
+    /// one is drawn and substituted into `member_table` on the spot;
+    /// otherwise the collision is merely reported so the host can decide
+    /// what, if anything, to do about the conflicting remote sources.
+    ///
+    /// Unlike a foreign SSRC, we always know what address *should* be
+    /// sending our own - so a packet claiming `our_ssrc` from anywhere but
+    /// `our_addr` is flagged the very first time it's seen, rather than
+    /// only once a second, differently-addressed impostor shows up. A
+    /// packet claiming `our_ssrc` from `our_addr` itself is just our own
+    /// transmission looping back, not a collision.
+    ///
+    /// Repeated sightings of the same (SSRC, address) pair within
+    /// `CONFLICT_SUPPRESS_N_INTERVALS` report intervals are suppressed, so a
+    /// transient routing loop doesn't cause us to reassign over and over.
+    fn check_collision(&mut self, ssrc: Ssrc, from: SocketAddr) -> Option<CollisionEvent> {
+        let suppress_window = self.deterministic_tx_interval() * CONFLICT_SUPPRESS_N_INTERVALS;
+
+        let mut expired: Vec<(Ssrc, SocketAddr)> = Vec::new();
+        for (&key, &seen_at) in self.conflicts.iter() {
+            if self.tc - seen_at > suppress_window {
+                expired.push(key);
+            }
+        }
+        for key in expired {
+            self.conflicts.remove(&key);
+        }
+
+        let previous = self.known_addrs.insert(ssrc, from);
+
+        let conflicted = if ssrc == self.our_ssrc {
+            from != self.our_addr
+        } else {
+            match previous {
+                Some(prev_addr) => prev_addr != from,
+                None => false,
+            }
+        };
+
+        if !conflicted {
+            return None;
+        }
+
+        if self.conflicts.contains_key(&(ssrc, from)) {
+            // Already handled this exact conflict recently; suppress.
+            return None;
+        }
+        self.conflicts.insert((ssrc, from), self.tc);
+
+        if ssrc == self.our_ssrc {
+            let old_ssrc = self.our_ssrc;
+
+            // TODO: signal the host application to send a BYE for old_ssrc
+
+            let mut new_ssrc = random::<u32>();
+            while self.member_table.contains_key(&new_ssrc) {
+                new_ssrc = random::<u32>();
+            }
+
+            self.member_table.remove(&old_ssrc);
+            self.member_table.insert(new_ssrc, Member {
+                id: new_ssrc, cname: None,
+                status: Some(MemberState::Listening), intervals: 0
+            });
+            self.our_ssrc = new_ssrc;
+
+            Some(CollisionEvent::OwnReassigned { old: old_ssrc, new: new_ssrc })
+        } else {
+            Some(CollisionEvent::ThirdParty(ssrc))
+        }
     }
 
     fn reverse_reconsideration(&mut self) {
@@ -257,7 +564,112 @@ impl State {
                 self.members += 1;
                 self.senders += 1;
             }
-        } 
+        }
+    }
+
+    /// Updates the running jitter and loss statistics for `ssrc` with a
+    /// newly-received RTP packet, per RFC 3550 Appendix A.1 (sequence
+    /// tracking) and A.8 (jitter).
+    #[allow(dead_code)]
+    fn update_receive_stats(&mut self, ssrc: Ssrc, seq: u16, rtp_ts: u32, arrival_rtp_ts: u32) {
+        if !self.receive_stats.contains_key(&ssrc) {
+            self.receive_stats.insert(ssrc, ReceiveStats::new(seq));
+        }
+
+        // RTP timestamps are a cyclic 32-bit counter, so a plain widen-then-
+        // subtract is wrong whenever exactly one of the two has wrapped;
+        // wrap the subtraction itself first, then reinterpret as signed.
+        let transit = (arrival_rtp_ts.wrapping_sub(rtp_ts)) as i32 as i64;
+        let stats = self.receive_stats.get_mut(&ssrc).unwrap();
+
+        let udelta = seq.wrapping_sub(stats.max_seq);
+        if udelta < MAX_DROPOUT {
+            if seq < stats.max_seq {
+                // Sequence wrapped around 2^16.
+                stats.cycles += 1;
+            }
+            stats.max_seq = seq;
+            stats.received += 1;
+            stats.bad_seq = None;
+        } else if udelta <= 0u16.wrapping_sub(MAX_MISORDER) {
+            // RFC 3550 A.1: a single wild jump is ignored in case it's just
+            // a corrupted or wildly-reordered packet; only resync once the
+            // *next* packet confirms the new sequence, so one bad packet
+            // can't wipe out a source's accumulated loss/jitter history.
+            if stats.bad_seq == Some(seq) {
+                stats.base_seq = seq;
+                stats.max_seq = seq;
+                stats.cycles = 0;
+                stats.received = 1;
+                stats.prior_expected = 0;
+                stats.prior_received = 0;
+                stats.bad_seq = None;
+            } else {
+                stats.bad_seq = Some(seq.wrapping_add(1));
+            }
+        } else {
+            // A late, reordered packet from before max_seq - still counts
+            // towards received, but doesn't move the high-water mark.
+            stats.received += 1;
+            stats.bad_seq = None;
+        }
+
+        // RFC 3550 A.8: J += (|D(i-1,i)| - J) / 16
+        if let Some(prev_transit) = stats.transit {
+            let d = (transit - prev_transit).abs() as f64;
+            stats.jitter += (d - stats.jitter) / 16.0;
+        }
+        stats.transit = Some(transit);
+    }
+
+    /// Snapshots the current jitter/loss statistics for `ssrc` into a
+    /// `ReportBlock` suitable for inclusion in an outgoing SR/RR, per RFC
+    /// 3550 Appendix A.3. Returns `None` if no RTP packets have been
+    /// received from `ssrc` yet.
+    ///
+    /// `last_sr`/`sr_delay` aren't populated here, since this crate doesn't
+    /// yet track received SR timestamps; callers fill those in separately.
+    #[allow(dead_code)]
+    pub fn snapshot_report_block(&mut self, ssrc: Ssrc) -> Option<ReportBlock> {
+        let (jitter, lost_total, fraction, highest_seq) = {
+            let stats = match self.receive_stats.get_mut(&ssrc) {
+                None => return None,
+                Some(stats) => stats,
+            };
+
+            let expected = stats.expected();
+            let lost_total = if expected > stats.received {
+                cmp::min(expected - stats.received, 0xffffff)
+            } else {
+                0
+            };
+
+            let expected_interval = expected - stats.prior_expected;
+            let received_interval = stats.received - stats.prior_received;
+            let lost_interval = expected_interval as i64 - received_interval as i64;
+
+            let fraction = if expected_interval == 0 || lost_interval <= 0 {
+                0
+            } else {
+                ((lost_interval << 8) / expected_interval as i64) as u8
+            };
+
+            stats.prior_expected = expected;
+            stats.prior_received = stats.received;
+
+            (stats.jitter as u32, lost_total, fraction,
+             ((stats.cycles << 16) | stats.max_seq as u32))
+        };
+
+        Some(ReportBlock {
+            ssrc: ssrc,
+            lost: fraction,
+            lost_total: lost_total,
+            highest_seq: highest_seq,
+            jitter: jitter,
+            last_sr: 0,
+            sr_delay: 0
+        })
     }
 
     #[allow(dead_code)]
@@ -270,8 +682,12 @@ impl State {
 
                 self.tp = self.tc;
                 self.tn = self.tc + self.tx_interval();
+
+                // A regular report just went out, so feedback may now be
+                // sent early until the next one is due (RFC 4585 3.5.3).
+                self.allow_early = true;
             },
-            
+
             cmp::Ordering::Greater => {
                 self.tn = self.tp + t;
             }
@@ -279,10 +695,132 @@ impl State {
 
         // TODO: set transmission timer to expire at time tn
 
+        self.sweep_stale_members();
 
         self.pmembers = self.members;
     }
 
+    /// Sweeps `member_table` for sources that have gone quiet, per RFC 3550
+    /// 6.3.5. Each call increments the `intervals` counter of every member
+    /// but ourselves (the counter is reset whenever a packet from that member
+    /// is seen); `tx_timer_expire` calls this once per expiry. A `Sending`
+    /// member whose counter reaches `RTCP_SENDER_TIMEOUT_N_INTERVALS` is
+    /// demoted back to `Listening`, and a `Listening` member whose counter
+    /// reaches `RTCP_SOURCE_TIMEOUT_N_INTERVALS` is dropped from the table
+    /// entirely. The timeouts count calls, not elapsed time; members that are
+    /// still unvalidated or have sent a BYE are never dropped.
+    ///
+    /// Returns the SSRCs that were pruned so the host application can tear
+    /// down any per-source state it keeps of its own.
+    #[allow(dead_code)]
+    pub fn sweep_stale_members(&mut self) -> Vec<Ssrc> {
+        let timeout = RTCP_SOURCE_TIMEOUT_N_INTERVALS;
+        let sender_timeout = RTCP_SENDER_TIMEOUT_N_INTERVALS;
+
+        let mut to_demote: Vec<Ssrc> = Vec::new();
+        let mut to_remove: Vec<Ssrc> = Vec::new();
+
+        for (&id, member) in self.member_table.iter_mut() {
+            if id == self.our_ssrc {
+                // RFC 3550's reference timeout code special-cases the local
+                // source: nothing but our own RTP sends resets our entry's
+                // `intervals`, so sweeping it would eventually time us out
+                // of our own member table.
+                continue;
+            }
+
+            member.intervals += 1;
+
+            match member.status {
+                Some(MemberState::Sending) if member.intervals >= sender_timeout => {
+                    to_demote.push(id);
+                },
+
+                Some(MemberState::Listening) if member.intervals >= timeout => {
+                    to_remove.push(id);
+                },
+
+                _ => (),
+            }
+        }
+
+        for &id in to_demote.iter() {
+            if let Some(member) = self.member_table.get_mut(&id) {
+                member.status = Some(MemberState::Listening);
+            }
+            self.senders -= 1;
+        }
+
+        for &id in to_remove.iter() {
+            self.member_table.remove(&id);
+            self.members -= 1;
+        }
+
+        if !to_remove.is_empty() {
+            self.reverse_reconsideration();
+        }
+
+        to_remove
+    }
+
+    /// Requests an out-of-band ("early") RTCP feedback transmission.
+    ///
+    /// Only meaningful when `profile` is `RtpProfile::Avpf`; under the plain
+    /// RFC 3550 profile, feedback can only ride on the regular schedule, so
+    /// this always returns `Suppressed`. Otherwise this follows the AVPF
+    /// algorithm from RFC 4585 section 3.5.3: if we're still inside the
+    /// mandatory regular-report-only window, timer reconsideration (6.3.3)
+    /// recomputes `tn` instead; if a report is already imminent, the request
+    /// is suppressed; otherwise a dithered send time is scheduled no sooner
+    /// than `T_rr_interval` after the last report.
+    #[allow(dead_code)]
+    pub fn request_early_rtcp(&mut self, now: SteadyTime) -> EarlyRtcpResult {
+        self.tc = now;
+
+        if self.profile == RtpProfile::Avp {
+            return EarlyRtcpResult::Suppressed;
+        }
+
+        if !self.allow_early {
+            self.tn = self.tp + self.t_rr_interval;
+            return EarlyRtcpResult::Reconsidered;
+        }
+
+        if self.tn <= self.tc {
+            // A report is already due no later than this request would fire.
+            return EarlyRtcpResult::Suppressed;
+        }
+
+        let two_party = self.members <= 2;
+        let t_dither_max = if two_party {
+            Duration::zero()
+        } else {
+            cmp::max(Duration::seconds(1), self.t_rr_interval / 2)
+        };
+
+        let t_dither = if t_dither_max == Duration::zero() {
+            Duration::zero()
+        } else {
+            let Closed01(rand) = random::<Closed01<f64>>();
+            let max_micros = t_dither_max.num_microseconds().unwrap_or(i64::MAX) as f64;
+            Duration::microseconds((max_micros * rand) as i64)
+        };
+
+        let candidate = cmp::max(self.tc + t_dither, self.tp + self.t_rr_interval);
+
+        if candidate < self.tn {
+            self.tn = candidate;
+            self.allow_early = false;
+            EarlyRtcpResult::Scheduled
+        } else {
+            // The regular schedule already beats the dithered candidate, so
+            // nothing was actually moved. Leave `allow_early` set so a
+            // later, more urgent request before the regular report ships
+            // can still succeed.
+            EarlyRtcpResult::Suppressed
+        }
+    }
+
     #[allow(unused_variables)]
     #[allow(dead_code)]
     fn pkt_send_notify(&mut self, packet_type: Option<PacketType>, packet_size: i32,
@@ -328,3 +866,266 @@ impl State {
         // TODO: signal the host application to send a BYE
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddrV4, Ipv4Addr};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port))
+    }
+
+    #[test]
+    fn sweep_stale_members_preserves_local_source_and_times_out_others() {
+        let our_ssrc = 0x1111;
+        let mut state = State::initialize(our_ssrc, addr(9000), 64000, 200);
+
+        // A remote listener shows up (validated by its second packet), then
+        // goes quiet.
+        state.update_member_status(0x2222, false);
+        state.update_member_status(0x2222, false);
+        assert_eq!(state.members, 2);
+
+        for _ in 0..RTCP_SOURCE_TIMEOUT_N_INTERVALS {
+            state.sweep_stale_members();
+        }
+
+        assert!(!state.member_table.contains_key(&0x2222));
+        // Our own entry must survive, even though nothing but our own RTP
+        // sends ever resets its `intervals` counter.
+        assert!(state.member_table.contains_key(&our_ssrc));
+        assert_eq!(state.members, 1);
+    }
+
+    #[test]
+    fn sweep_stale_members_demotes_quiet_senders_before_removing_them() {
+        let our_ssrc = 0x1111;
+        let mut state = State::initialize(our_ssrc, addr(9000), 64000, 200);
+
+        state.update_member_status(0x3333, true); // validated immediately as a sender
+        assert_eq!(state.senders, 1);
+
+        for _ in 0..RTCP_SENDER_TIMEOUT_N_INTERVALS {
+            state.sweep_stale_members();
+        }
+
+        // Demoted back to Listening, not yet removed from the table.
+        assert_eq!(state.senders, 0);
+        assert!(state.member_table.contains_key(&0x3333));
+    }
+
+    #[test]
+    fn update_receive_stats_tracks_sequence_wraparound() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+        let ssrc = 0x2222;
+
+        state.update_receive_stats(ssrc, 0xfffe, 0, 0);
+        state.update_receive_stats(ssrc, 0xffff, 0, 0);
+        state.update_receive_stats(ssrc, 0x0000, 0, 0);
+        state.update_receive_stats(ssrc, 0x0001, 0, 0);
+
+        let stats = state.receive_stats.get(&ssrc).unwrap();
+        assert_eq!(stats.cycles, 1);
+        assert_eq!(stats.max_seq, 0x0001);
+        assert_eq!(stats.received, 4);
+    }
+
+    #[test]
+    fn update_receive_stats_requires_next_packet_to_confirm_a_large_jump() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+        let ssrc = 0x3333;
+
+        state.update_receive_stats(ssrc, 10, 0, 0);
+        // A huge jump looks like the source restarted, but a single packet
+        // isn't enough to resync on - it could just be corrupt.
+        state.update_receive_stats(ssrc, 40000, 0, 0);
+        {
+            let stats = state.receive_stats.get(&ssrc).unwrap();
+            assert_eq!(stats.max_seq, 10);
+            assert_eq!(stats.base_seq, 10);
+        }
+
+        // The very next packet continues on from the jumped-to sequence
+        // number, confirming the source really did restart.
+        state.update_receive_stats(ssrc, 40001, 0, 0);
+        let stats = state.receive_stats.get(&ssrc).unwrap();
+        assert_eq!(stats.max_seq, 40001);
+        assert_eq!(stats.base_seq, 40001);
+    }
+
+    #[test]
+    fn update_receive_stats_handles_rtp_timestamp_wraparound() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+        let ssrc = 0x4444;
+
+        // Transit is a constant 8 ticks both times, but the RTP clock wraps
+        // past 2^32 between the two packets. A plain widen-then-subtract
+        // would read the second transit as wildly negative instead of 8,
+        // spiking the jitter estimate even though nothing actually changed.
+        state.update_receive_stats(ssrc, 1, 0xfffffff0, 0xfffffff8);
+        state.update_receive_stats(ssrc, 2, 0xfffffffc, 0x00000004);
+
+        let stats = state.receive_stats.get(&ssrc).unwrap();
+        assert_eq!(stats.jitter, 0.0);
+    }
+
+    #[test]
+    fn check_collision_suppresses_a_repeated_conflict_within_the_window() {
+        let our_ssrc = 0x1111;
+        let mut state = State::initialize(our_ssrc, addr(9000), 64000, 200);
+
+        let remote_ssrc = 0x2222;
+        let addr_a = addr(10000);
+        let addr_b = addr(20000);
+
+        // First sighting of `remote_ssrc` just establishes its address.
+        assert!(state.check_collision(remote_ssrc, addr_a).is_none());
+
+        // A different address claiming the same SSRC is a genuine conflict.
+        match state.check_collision(remote_ssrc, addr_b) {
+            Some(CollisionEvent::ThirdParty(ssrc)) => assert_eq!(ssrc, remote_ssrc),
+            _ => panic!("expected a reported collision"),
+        }
+
+        // Address flips back, which is also a (new) conflict.
+        match state.check_collision(remote_ssrc, addr_a) {
+            Some(CollisionEvent::ThirdParty(ssrc)) => assert_eq!(ssrc, remote_ssrc),
+            _ => panic!("expected a reported collision"),
+        }
+
+        // Seeing (remote_ssrc, addr_b) again, which was already handled
+        // above, must be suppressed rather than re-reported.
+        assert!(state.check_collision(remote_ssrc, addr_b).is_none());
+    }
+
+    #[test]
+    fn check_collision_reassigns_our_own_ssrc_on_first_foreign_sighting() {
+        let our_ssrc = 0x1111;
+        let our_addr = addr(9000);
+        let mut state = State::initialize(our_ssrc, our_addr, 64000, 200);
+
+        // Unlike a foreign SSRC, we always know what address *should* be
+        // sending our_ssrc, so a single differently-addressed sighting is
+        // already a collision - no second impostor required.
+        match state.check_collision(our_ssrc, addr(20000)) {
+            Some(CollisionEvent::OwnReassigned { old, new }) => {
+                assert_eq!(old, our_ssrc);
+                assert!(state.member_table.contains_key(&new));
+                assert!(!state.member_table.contains_key(&old));
+            },
+            _ => panic!("expected our own SSRC to be reassigned on first sighting"),
+        }
+    }
+
+    #[test]
+    fn check_collision_ignores_our_own_packet_looping_back() {
+        let our_ssrc = 0x1111;
+        let our_addr = addr(9000);
+        let mut state = State::initialize(our_ssrc, our_addr, 64000, 200);
+
+        // A packet claiming our own SSRC from our own address is just our
+        // own transmission looping back, not a collision.
+        assert!(state.check_collision(our_ssrc, our_addr).is_none());
+        assert!(state.member_table.contains_key(&our_ssrc));
+    }
+
+    #[test]
+    fn request_early_rtcp_is_always_suppressed_under_avp() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+
+        match state.request_early_rtcp(SteadyTime::now()) {
+            EarlyRtcpResult::Suppressed => (),
+            _ => panic!("expected Suppressed under the plain AVP profile"),
+        }
+    }
+
+    #[test]
+    fn request_early_rtcp_reconsiders_outside_the_allowed_window() {
+        let mut state = State::initialize_avpf(0x1111, addr(9000), 64000, 200,
+                                               Duration::milliseconds(100));
+
+        // Fresh from initialize_avpf, allow_early is false until a regular
+        // report has gone out, so an early request must be reconsidered
+        // rather than scheduled.
+        match state.request_early_rtcp(SteadyTime::now()) {
+            EarlyRtcpResult::Reconsidered => (),
+            _ => panic!("expected Reconsidered while outside the allowed window"),
+        }
+        assert_eq!(state.tn, state.tp + state.t_rr_interval);
+    }
+
+    #[test]
+    fn request_early_rtcp_schedules_when_dither_beats_the_regular_schedule() {
+        let mut state = State::initialize_avpf(0x1111, addr(9000), 64000, 200,
+                                               Duration::milliseconds(100));
+        let now = SteadyTime::now();
+        state.tp = now;
+        state.tn = now + Duration::seconds(10);
+        state.allow_early = true;
+
+        match state.request_early_rtcp(now) {
+            EarlyRtcpResult::Scheduled => (),
+            _ => panic!("expected Scheduled when the dither beats the regular schedule"),
+        }
+        assert!(state.tn < now + Duration::seconds(10));
+        assert!(!state.allow_early);
+    }
+
+    #[test]
+    fn request_early_rtcp_suppresses_without_clearing_allow_early_when_already_beaten() {
+        let mut state = State::initialize_avpf(0x1111, addr(9000), 64000, 200,
+                                               Duration::seconds(5));
+        let now = SteadyTime::now();
+        state.tp = now;
+        // The regular schedule is already due sooner than any dithered
+        // candidate could be.
+        state.tn = now + Duration::milliseconds(1);
+        state.allow_early = true;
+
+        match state.request_early_rtcp(now) {
+            EarlyRtcpResult::Suppressed => (),
+            _ => panic!("expected Suppressed when the regular schedule already wins"),
+        }
+        // Nothing was actually scheduled, so a later, more urgent request
+        // before the regular report ships must still be able to succeed.
+        assert!(state.allow_early);
+        assert_eq!(state.tn, now + Duration::milliseconds(1));
+    }
+
+    #[test]
+    fn deterministic_tx_interval_uses_sender_share_when_we_sent() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+        state.set_bandwidth_fractions(400, 40000);
+        state.avg_rtcp_size = 100000.0;
+        state.members = 10;
+        state.senders = 3;
+        state.we_sent = true;
+
+        assert_eq!(state.deterministic_tx_interval(), Duration::seconds(750));
+    }
+
+    #[test]
+    fn deterministic_tx_interval_uses_receiver_share_when_not_we_sent() {
+        let mut state = State::initialize(0x1111, addr(9000), 64000, 200);
+        state.set_bandwidth_fractions(400, 40000);
+        state.avg_rtcp_size = 100000.0;
+        state.members = 10;
+        state.senders = 3;
+        state.we_sent = false;
+
+        assert_eq!(state.deterministic_tx_interval(), Duration::microseconds(17500000));
+    }
+
+    #[test]
+    fn deterministic_tx_interval_clamps_near_zero_bandwidth_to_the_minimum() {
+        // Configured bandwidth of 1 octet/sec must be clamped up to
+        // MIN_RTCP_BANDWIDTH before it's used as a divisor.
+        let mut state = State::initialize(0x1111, addr(9000), 1, 200);
+        state.avg_rtcp_size = 100000.0;
+        state.members = 10;
+        state.senders = 0;
+
+        assert_eq!(state.deterministic_tx_interval(), Duration::seconds(1875));
+    }
+}